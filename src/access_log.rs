@@ -0,0 +1,149 @@
+//! Optional rotating access log: one line per proxied request, written by a
+//! dedicated background task so file I/O never blocks the proxy hot path.
+//! Independent of the structured `tracing` JSON stream, so the service can
+//! keep a durable on-disk audit trail without a log shipper.
+
+use std::path::PathBuf;
+
+use tokio::io::AsyncWriteExt;
+use tokio::sync::mpsc;
+
+use crate::config::AccessLogConfig;
+
+#[derive(Clone)]
+pub struct AccessLog {
+    tx: mpsc::UnboundedSender<String>,
+}
+
+pub struct AccessLogEntry<'a> {
+    pub method: &'a str,
+    pub path: &'a str,
+    pub status: u16,
+    pub latency_ms: u128,
+    pub bytes: &'a str,
+    pub ua: &'a str,
+    pub artifact_type: &'static str,
+    pub package: Option<&'a str>,
+    pub version: Option<&'a str>,
+}
+
+impl AccessLog {
+    /// Spawn the background writer; returns `None` if no path is configured,
+    /// in which case the access log is simply disabled.
+    pub fn start(config: &AccessLogConfig) -> Option<Self> {
+        let path = PathBuf::from(config.path.as_ref()?);
+        let (tx, rx) = mpsc::unbounded_channel();
+        let writer = Writer {
+            path,
+            rotate_max_bytes: config.rotate_max_bytes,
+            rotate_daily: config.rotate_daily,
+            retain: config.retain,
+        };
+        tokio::spawn(writer.run(rx));
+        Some(Self { tx })
+    }
+
+    pub fn log(&self, entry: AccessLogEntry<'_>) {
+        let timestamp = chrono::Utc::now().to_rfc3339();
+        let line = format!(
+            "{timestamp}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\n",
+            entry.method,
+            sanitize_field(entry.path),
+            entry.status,
+            entry.latency_ms,
+            entry.bytes,
+            sanitize_field(entry.ua),
+            entry.artifact_type,
+            entry.package.unwrap_or("-"),
+            entry.version.unwrap_or("-"),
+        );
+        // Best-effort: the channel is unbounded so sending never blocks, and a
+        // closed channel (writer task gone) just drops the line rather than
+        // erroring out the request that triggered it.
+        let _ = self.tx.send(line);
+    }
+}
+
+/// Replace tab/CR/LF and other control bytes with a space so client-controlled
+/// fields (request path, User-Agent) can't inject or shift tab-delimited
+/// columns in the log line. `HeaderValue` only rejects CR/LF, not tabs, so
+/// this can't be skipped even though the format line above already uses `\t`
+/// as the column separator.
+fn sanitize_field(s: &str) -> std::borrow::Cow<'_, str> {
+    if s.bytes().any(|b| b.is_ascii_control()) {
+        std::borrow::Cow::Owned(
+            s.chars().map(|c| if c.is_ascii_control() { ' ' } else { c }).collect(),
+        )
+    } else {
+        std::borrow::Cow::Borrowed(s)
+    }
+}
+
+struct Writer {
+    path: PathBuf,
+    rotate_max_bytes: u64,
+    rotate_daily: bool,
+    retain: u32,
+}
+
+impl Writer {
+    async fn run(self, mut rx: mpsc::UnboundedReceiver<String>) {
+        let mut file = match self.open().await {
+            Ok(f) => f,
+            Err(e) => {
+                tracing::warn!(error=%e, path=%self.path.display(), "access_log_open_failed");
+                return;
+            }
+        };
+        let mut day = chrono::Utc::now().date_naive();
+
+        while let Some(line) = rx.recv().await {
+            let today = chrono::Utc::now().date_naive();
+            let day_changed = self.rotate_daily && today != day;
+            let size_exceeded = self.rotate_max_bytes > 0
+                && file.metadata().await.map(|m| m.len()).unwrap_or(0) >= self.rotate_max_bytes;
+
+            if day_changed || size_exceeded {
+                if let Err(e) = self.rotate().await {
+                    tracing::warn!(error=%e, "access_log_rotate_failed");
+                }
+                file = match self.open().await {
+                    Ok(f) => f,
+                    Err(e) => {
+                        tracing::warn!(error=%e, "access_log_reopen_failed");
+                        return;
+                    }
+                };
+                day = today;
+            }
+
+            if let Err(e) = file.write_all(line.as_bytes()).await {
+                tracing::warn!(error=%e, "access_log_write_failed");
+            }
+        }
+    }
+
+    async fn open(&self) -> std::io::Result<tokio::fs::File> {
+        tokio::fs::OpenOptions::new().create(true).append(true).open(&self.path).await
+    }
+
+    /// Shift `path.N` -> `path.N+1` up to `retain`, dropping the oldest,
+    /// then move the current file to `path.1`.
+    async fn rotate(&self) -> std::io::Result<()> {
+        if self.retain == 0 {
+            let _ = tokio::fs::remove_file(&self.path).await;
+            return Ok(());
+        }
+        let _ = tokio::fs::remove_file(self.rotated_path(self.retain)).await;
+        for n in (1..self.retain).rev() {
+            let _ = tokio::fs::rename(self.rotated_path(n), self.rotated_path(n + 1)).await;
+        }
+        tokio::fs::rename(&self.path, self.rotated_path(1)).await
+    }
+
+    fn rotated_path(&self, n: u32) -> PathBuf {
+        let mut os = self.path.clone().into_os_string();
+        os.push(format!(".{n}"));
+        PathBuf::from(os)
+    }
+}
@@ -0,0 +1,228 @@
+//! Content-addressed, size-bounded on-disk cache for upstream CRAN responses.
+//!
+//! CRAN package artifacts (`src_tar`, `archive_tar`, `win_zip`, `mac_tgz`) are
+//! immutable once published, so they're cached forever once fetched. Index
+//! files (`index_text`/`index_gz`/`index_rds`) change over time, so they're
+//! cached too but always revalidated against the upstream `ETag`/
+//! `Last-Modified` before being served from disk.
+//!
+//! Entries are stored as a pair of files under `<dir>/<key[0..2]>/<key>`:
+//! `<key>.body` (the raw response bytes) and `<key>.meta.json` (the metadata
+//! needed to rebuild response headers and to revalidate).
+
+use std::io;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+
+use bytes::Bytes;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use tokio::io::AsyncWriteExt;
+
+/// Outcome of a cache lookup for a single request, surfaced as the `cache`
+/// field of the structured `proxied` log line.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CacheOutcome {
+    Hit,
+    Miss,
+    Revalidated,
+    Bypass,
+}
+
+impl CacheOutcome {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            CacheOutcome::Hit => "hit",
+            CacheOutcome::Miss => "miss",
+            CacheOutcome::Revalidated => "revalidated",
+            CacheOutcome::Bypass => "bypass",
+        }
+    }
+}
+
+/// Metadata persisted alongside a cached response body.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct CachedMeta {
+    pub etag: Option<String>,
+    pub last_modified: Option<String>,
+    pub content_type: Option<String>,
+    pub content_length: Option<u64>,
+    pub immutable: bool,
+}
+
+#[derive(Clone)]
+pub struct Cache {
+    dir: PathBuf,
+    max_bytes: u64,
+    /// Disambiguates temp file names for concurrent misses on the same key
+    /// within this process; the pid alone collides under a thundering herd.
+    tmp_counter: Arc<AtomicU64>,
+}
+
+impl Cache {
+    pub fn new(dir: impl Into<PathBuf>, max_bytes: u64) -> Self {
+        Self { dir: dir.into(), max_bytes, tmp_counter: Arc::new(AtomicU64::new(0)) }
+    }
+
+    pub async fn init(&self) -> io::Result<()> {
+        tokio::fs::create_dir_all(&self.dir).await
+    }
+
+    /// Derive the on-disk key for a request path. CRAN artifact paths embed
+    /// the package name and version, so the path alone is a stable key.
+    pub fn key_for(path: &str) -> String {
+        let mut hasher = Sha256::new();
+        hasher.update(path.as_bytes());
+        format!("{:x}", hasher.finalize())
+    }
+
+    fn shard_dir(&self, key: &str) -> PathBuf {
+        self.dir.join(&key[0..2])
+    }
+
+    fn body_path(&self, key: &str) -> PathBuf {
+        self.shard_dir(key).join(format!("{key}.body"))
+    }
+
+    fn meta_path(&self, key: &str) -> PathBuf {
+        self.shard_dir(key).join(format!("{key}.meta.json"))
+    }
+
+    fn tmp_path(&self, key: &str) -> PathBuf {
+        let n = self.tmp_counter.fetch_add(1, Ordering::Relaxed);
+        self.shard_dir(key).join(format!("{key}.{}.{n}.tmp", std::process::id()))
+    }
+
+    /// Look up a cached entry; only returns `Some` if both the body and the
+    /// metadata file are present on disk. Bumps the body file's mtime so
+    /// `evict_inner`'s mtime sort reflects last access, not just last write.
+    pub async fn lookup(&self, key: &str) -> Option<CachedMeta> {
+        let meta_bytes = tokio::fs::read(self.meta_path(key)).await.ok()?;
+        if tokio::fs::metadata(self.body_path(key)).await.is_err() {
+            return None;
+        }
+        self.touch(key).await;
+        serde_json::from_slice(&meta_bytes).ok()
+    }
+
+    /// Best-effort: bump the cached body's mtime to now. A failure here just
+    /// means this entry is evicted a little earlier than ideal, never a hard
+    /// error for the lookup that triggered it.
+    async fn touch(&self, key: &str) {
+        let path = self.body_path(key);
+        let _ = tokio::task::spawn_blocking(move || {
+            std::fs::File::open(&path).and_then(|f| f.set_modified(std::time::SystemTime::now()))
+        })
+        .await;
+    }
+
+    pub async fn open_body(&self, key: &str) -> io::Result<tokio::fs::File> {
+        tokio::fs::File::open(self.body_path(key)).await
+    }
+
+    /// Write (or overwrite) just the metadata file for an entry;
+    /// [`PendingWrite::commit`] calls this once the body is in place.
+    pub async fn refresh_meta(&self, key: &str, meta: &CachedMeta) -> io::Result<()> {
+        tokio::fs::write(self.meta_path(key), serde_json::to_vec(meta)?).await
+    }
+
+    /// Begin a tee'd write: bytes are appended via [`PendingWrite::write_chunk`]
+    /// as they stream to the client, and only become visible to other
+    /// lookups once [`PendingWrite::commit`] atomically renames them into
+    /// place.
+    pub async fn begin_write(&self, key: &str) -> io::Result<PendingWrite> {
+        let dir = self.shard_dir(key);
+        tokio::fs::create_dir_all(&dir).await?;
+        let tmp = self.tmp_path(key);
+        let file = tokio::fs::File::create(&tmp).await?;
+        Ok(PendingWrite { cache: self.clone(), key: key.to_string(), tmp, file, written: 0 })
+    }
+
+    /// Evict least-recently-used entries (oldest body mtime, which
+    /// [`Cache::lookup`] bumps on every hit) until the cache fits within
+    /// `max_bytes`. A `max_bytes` of 0 disables eviction.
+    pub async fn evict_if_needed(&self) {
+        if self.max_bytes == 0 {
+            return;
+        }
+        if let Err(e) = self.evict_inner().await {
+            tracing::warn!(error=%e, "cache_evict_failed");
+        }
+    }
+
+    async fn evict_inner(&self) -> io::Result<()> {
+        let mut entries: Vec<(std::time::SystemTime, String, u64)> = Vec::new();
+        let mut total: u64 = 0;
+
+        let mut shards = tokio::fs::read_dir(&self.dir).await?;
+        while let Some(shard) = shards.next_entry().await? {
+            if !shard.file_type().await?.is_dir() {
+                continue;
+            }
+            let mut files = tokio::fs::read_dir(shard.path()).await?;
+            while let Some(f) = files.next_entry().await? {
+                let name = f.file_name();
+                let name = name.to_string_lossy();
+                let Some(key) = name.strip_suffix(".body") else { continue };
+                let meta = f.metadata().await?;
+                total += meta.len();
+                entries.push((meta.modified()?, key.to_string(), meta.len()));
+            }
+        }
+
+        if total <= self.max_bytes {
+            return Ok(());
+        }
+        entries.sort_by_key(|(mtime, _, _)| *mtime);
+        for (_, key, size) in entries {
+            if total <= self.max_bytes {
+                break;
+            }
+            let _ = tokio::fs::remove_file(self.body_path(&key)).await;
+            let _ = tokio::fs::remove_file(self.meta_path(&key)).await;
+            total = total.saturating_sub(size);
+        }
+        Ok(())
+    }
+}
+
+/// A write-in-progress for a single cache entry. Dropping this without
+/// calling [`commit`](PendingWrite::commit) or [`abort`](PendingWrite::abort)
+/// leaves a stray temp file, so callers should always finish one or the
+/// other.
+pub struct PendingWrite {
+    cache: Cache,
+    key: String,
+    tmp: PathBuf,
+    file: tokio::fs::File,
+    written: u64,
+}
+
+impl PendingWrite {
+    pub async fn write_chunk(&mut self, chunk: &Bytes) -> io::Result<()> {
+        self.file.write_all(chunk).await?;
+        self.written += chunk.len() as u64;
+        Ok(())
+    }
+
+    pub fn written(&self) -> u64 {
+        self.written
+    }
+
+    /// Atomically rename the temp file into place and persist `meta`, then
+    /// sweep for eviction. Only call this once the full body has been
+    /// received and validated (status 200, byte count matches
+    /// `Content-Length` when present).
+    pub async fn commit(mut self, meta: CachedMeta) -> io::Result<()> {
+        self.file.flush().await?;
+        tokio::fs::rename(&self.tmp, self.cache.body_path(&self.key)).await?;
+        self.cache.refresh_meta(&self.key, &meta).await?;
+        self.cache.evict_if_needed().await;
+        Ok(())
+    }
+
+    pub async fn abort(self) {
+        let _ = tokio::fs::remove_file(&self.tmp).await;
+    }
+}
@@ -0,0 +1,92 @@
+//! On-the-fly gzip/deflate compression of upstream responses that arrive
+//! uncompressed, negotiated against the client's `Accept-Encoding` header.
+//! Applied only to the plain-text `PACKAGES` index (`index_text`) — already
+//! compressed artifacts (`.tar.gz`, `.zip`, `.tgz`, `PACKAGES.gz`) are left
+//! untouched — and only when opted into via config.
+
+use async_compression::tokio::bufread::{DeflateEncoder, GzipEncoder};
+use axum::body::Body;
+use axum::http::{header, HeaderMap, HeaderValue};
+use futures_util::TryStreamExt;
+use tokio_util::io::{ReaderStream, StreamReader};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Encoding {
+    Gzip,
+    Deflate,
+}
+
+impl Encoding {
+    fn as_str(self) -> &'static str {
+        match self {
+            Encoding::Gzip => "gzip",
+            Encoding::Deflate => "deflate",
+        }
+    }
+}
+
+/// Pick the best supported encoding from an `Accept-Encoding` header,
+/// honoring `q=0` exclusions and otherwise preferring the client's listed
+/// order.
+pub fn negotiate(accept_encoding: &str) -> Option<Encoding> {
+    for part in accept_encoding.split(',') {
+        let mut fields = part.split(';');
+        let name = fields.next()?.trim();
+        let q: f32 = fields
+            .find_map(|p| p.trim().strip_prefix("q="))
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(1.0);
+        if q <= 0.0 {
+            continue;
+        }
+        match name {
+            "gzip" => return Some(Encoding::Gzip),
+            "deflate" => return Some(Encoding::Deflate),
+            _ => continue,
+        }
+    }
+    None
+}
+
+/// Wrap `body` in a streaming encoder for `encoding`, updating `headers` to
+/// reflect the new `Content-Encoding` and dropping the now-invalid
+/// `Content-Length`.
+pub fn compress_body(encoding: Encoding, body: Body, headers: &mut HeaderMap) -> Body {
+    headers.remove(header::CONTENT_LENGTH);
+    headers.insert(header::CONTENT_ENCODING, HeaderValue::from_static(encoding.as_str()));
+
+    let reader = StreamReader::new(
+        body.into_data_stream()
+            .map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e)),
+    );
+    match encoding {
+        Encoding::Gzip => Body::from_stream(ReaderStream::new(GzipEncoder::new(reader))),
+        Encoding::Deflate => Body::from_stream(ReaderStream::new(DeflateEncoder::new(reader))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn negotiate_prefers_client_listed_order() {
+        assert_eq!(negotiate("deflate, gzip"), Some(Encoding::Deflate));
+        assert_eq!(negotiate("gzip, deflate"), Some(Encoding::Gzip));
+    }
+
+    #[test]
+    fn negotiate_honors_q_zero_exclusion() {
+        assert_eq!(negotiate("gzip;q=0, deflate"), Some(Encoding::Deflate));
+    }
+
+    #[test]
+    fn negotiate_skips_unsupported_encodings() {
+        assert_eq!(negotiate("br, gzip"), Some(Encoding::Gzip));
+    }
+
+    #[test]
+    fn negotiate_returns_none_when_nothing_supported() {
+        assert_eq!(negotiate("br, identity"), None);
+    }
+}
@@ -0,0 +1,119 @@
+//! TOML-based configuration: listen address, the upstream CRAN mirror pool,
+//! timeouts, and cache settings.
+//!
+//! Loaded once at startup from the path given by `--config` or the
+//! `CONFIG_FILE` env var.
+
+use std::path::Path;
+use std::time::Duration;
+
+use serde::Deserialize;
+use url::Url;
+
+#[derive(Debug, Deserialize)]
+pub struct Config {
+    #[serde(default = "default_listen_addr")]
+    pub listen_addr: String,
+    /// Ordered pool of upstream CRAN mirrors; the first healthy one is
+    /// tried first, with failover to the next on connect error, 5xx, or
+    /// timeout.
+    pub upstreams: Vec<Url>,
+    #[serde(default = "default_connect_timeout_ms")]
+    pub connect_timeout_ms: u64,
+    #[serde(default = "default_request_timeout_ms")]
+    pub request_timeout_ms: u64,
+    #[serde(default = "default_health_check_interval_secs")]
+    pub health_check_interval_secs: u64,
+    #[serde(default)]
+    pub cache: CacheConfig,
+    /// Opt-in: transparently gzip/deflate-compress uncompressed index
+    /// responses when the client's `Accept-Encoding` allows it.
+    #[serde(default)]
+    pub compress_index_responses: bool,
+    /// `max-age` sent for immutable versioned artifacts (`src_tar`,
+    /// `archive_tar`, `win_zip`, `mac_tgz`).
+    #[serde(default = "default_immutable_max_age_secs")]
+    pub immutable_max_age_secs: u64,
+    /// `max-age` sent for index artifacts (`index_text`/`index_gz`/`index_rds`).
+    #[serde(default = "default_index_max_age_secs")]
+    pub index_max_age_secs: u64,
+    #[serde(default)]
+    pub access_log: AccessLogConfig,
+}
+
+/// Settings for the optional rotating access log file. Absent `path` means
+/// the access log is disabled.
+#[derive(Debug, Deserialize, Default)]
+pub struct AccessLogConfig {
+    pub path: Option<String>,
+    #[serde(default = "default_access_log_rotate_max_bytes")]
+    pub rotate_max_bytes: u64,
+    #[serde(default)]
+    pub rotate_daily: bool,
+    #[serde(default = "default_access_log_retain")]
+    pub retain: u32,
+}
+
+fn default_access_log_rotate_max_bytes() -> u64 { 100 * 1024 * 1024 }
+fn default_access_log_retain() -> u32 { 5 }
+
+#[derive(Debug, Deserialize)]
+pub struct CacheConfig {
+    #[serde(default = "default_cache_dir")]
+    pub dir: String,
+    #[serde(default = "default_cache_max_bytes")]
+    pub max_bytes: u64,
+}
+
+impl Default for CacheConfig {
+    fn default() -> Self {
+        Self { dir: default_cache_dir(), max_bytes: default_cache_max_bytes() }
+    }
+}
+
+fn default_listen_addr() -> String { "0.0.0.0:8080".to_string() }
+fn default_connect_timeout_ms() -> u64 { 5_000 }
+fn default_request_timeout_ms() -> u64 { 60_000 }
+fn default_health_check_interval_secs() -> u64 { 30 }
+fn default_cache_dir() -> String { "./cache-data".to_string() }
+fn default_cache_max_bytes() -> u64 { 5 * 1024 * 1024 * 1024 }
+fn default_immutable_max_age_secs() -> u64 { 31_536_000 }
+fn default_index_max_age_secs() -> u64 { 60 }
+
+impl Config {
+    pub fn load(path: &Path) -> anyhow::Result<Self> {
+        let text = std::fs::read_to_string(path)
+            .map_err(|e| anyhow::anyhow!("reading config {}: {e}", path.display()))?;
+        let cfg: Config = toml::from_str(&text)
+            .map_err(|e| anyhow::anyhow!("parsing config {}: {e}", path.display()))?;
+        if cfg.upstreams.is_empty() {
+            anyhow::bail!("config must list at least one upstream mirror");
+        }
+        Ok(cfg)
+    }
+
+    /// Path to load the config from: `--config <path>` if present among
+    /// `args`, otherwise the `CONFIG_FILE` env var, otherwise `config.toml`.
+    pub fn path_from_args(args: &[String]) -> std::path::PathBuf {
+        for pair in args.windows(2) {
+            if pair[0] == "--config" {
+                return std::path::PathBuf::from(&pair[1]);
+            }
+        }
+        std::env::var("CONFIG_FILE")
+            .map(std::path::PathBuf::from)
+            .unwrap_or_else(|_| std::path::PathBuf::from("config.toml"))
+    }
+
+    pub fn connect_timeout(&self) -> Duration {
+        Duration::from_millis(self.connect_timeout_ms)
+    }
+
+    pub fn request_timeout(&self) -> Duration {
+        Duration::from_millis(self.request_timeout_ms)
+    }
+
+    pub fn health_check_interval(&self) -> Duration {
+        Duration::from_secs(self.health_check_interval_secs)
+    }
+}
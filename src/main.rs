@@ -1,26 +1,43 @@
+mod access_log;
+mod cache;
+mod compress;
+mod config;
+mod metrics;
+mod mirrors;
+
+use access_log::{AccessLog, AccessLogEntry};
 use axum::{
     body::Body,
     extract::{OriginalUri, State},
-    http::{HeaderMap, Method, StatusCode},
+    http::{header, HeaderMap, HeaderValue, Method, StatusCode},
     response::Response,
-    routing::any,
+    routing::{any, get},
     Router,
 };
+use cache::{Cache, CacheOutcome, CachedMeta};
+use config::Config;
+use futures_util::StreamExt;
+use metrics::Metrics;
+use mirrors::MirrorPool;
 use once_cell::sync::Lazy;
 use regex::Regex;
 use serde::Serialize;
-use std::{net::SocketAddr, time::Instant};
+use std::{net::SocketAddr, sync::Arc, time::Instant};
 use tracing::info;
 use tracing_subscriber::{fmt, layer::SubscriberExt, util::SubscriberInitExt};
 use url::Url;
 
 #[derive(Clone)]
 struct AppState {
-    upstream: Url,
+    mirrors: MirrorPool,
     client: reqwest::Client,
+    cache: Cache,
+    metrics: Arc<Metrics>,
+    config: Arc<Config>,
+    access_log: Option<AccessLog>,
 }
 
-#[derive(Serialize, Default)]
+#[derive(Serialize, Default, Clone)]
 struct CranInfo {
     artifact_type: &'static str,
     package: Option<String>,
@@ -56,6 +73,12 @@ fn parse_cran(path: &str) -> CranInfo {
     CranInfo { artifact_type: "unknown", ..Default::default() }
 }
 
+/// Whether `artifact_type` names a CRAN path that is immutable once
+/// published, and so can be cached forever rather than revalidated.
+fn is_immutable_artifact(artifact_type: &str) -> bool {
+    matches!(artifact_type, "src_tar" | "archive_tar" | "win_zip" | "mac_tgz")
+}
+
 // Remove hop-by-hop and sensitive headers; let reqwest set correct Host
 fn strip_hop_headers(headers: &mut HeaderMap) {
     for name in ["connection","proxy-connection","keep-alive","transfer-encoding","te","upgrade","trailer","host"] {
@@ -71,34 +94,253 @@ async fn main() -> anyhow::Result<()> {
         .with(tracing_subscriber::EnvFilter::from_default_env())
         .init();
 
-    let upstream = std::env::var("UPSTREAM_BASE")
-        .unwrap_or_else(|_| "https://cloud.r-project.org".to_string());
-    let upstream = Url::parse(&upstream)?;
+    let args: Vec<String> = std::env::args().collect();
+    let config_path = Config::path_from_args(&args);
+    let config = Arc::new(Config::load(&config_path)?);
 
     let client = reqwest::Client::builder()
         .http2_adaptive_window(true)
         .pool_max_idle_per_host(8)
-        .connect_timeout(std::time::Duration::from_secs(5))
-        .timeout(std::time::Duration::from_secs(60))
+        .connect_timeout(config.connect_timeout())
+        .timeout(config.request_timeout())
         .use_rustls_tls()
         .build()?;
 
-    let state = AppState { upstream, client };
+    let mirrors = MirrorPool::new(config.upstreams.clone());
+    mirrors.warm_up(&client).await;
+    tokio::spawn(mirrors.clone().run_health_checks(client.clone(), config.health_check_interval()));
+
+    let cache = Cache::new(config.cache.dir.clone(), config.cache.max_bytes);
+    cache.init().await?;
+
+    let metrics = Arc::new(Metrics::new());
+    let access_log = AccessLog::start(&config.access_log);
+
+    let state = AppState { mirrors, client, cache, metrics, config, access_log };
 
     let app = Router::new()
         .route("/healthz", any(|| async { "ok" }))
+        .route("/metrics", get(metrics_handler))
         .fallback(any(proxy))
         .with_state(state);
 
-    let addr: SocketAddr = std::env::var("LISTEN_ADDR")
-        .unwrap_or_else(|_| "0.0.0.0:8080".into())
-        .parse()?;
+    let addr: SocketAddr = config.listen_addr.parse()?;
     let listener = tokio::net::TcpListener::bind(addr).await?;
-    tracing::info!(%addr, "listening");
+    tracing::info!(%addr, config=%config_path.display(), "listening");
     axum::serve(listener, app).await?;
     Ok(())
 }
 
+async fn metrics_handler(State(state): State<AppState>) -> impl axum::response::IntoResponse {
+    (
+        [(header::CONTENT_TYPE, "text/plain; version=0.0.4")],
+        state.metrics.render(),
+    )
+}
+
+#[allow(clippy::too_many_arguments)]
+fn log_proxied(
+    metrics: &Metrics,
+    access_log: Option<&AccessLog>,
+    method: &str,
+    path: &str,
+    status: u16,
+    started: Instant,
+    ua: &str,
+    range: &str,
+    etag_out: &str,
+    content_length: &str,
+    derived: &CranInfo,
+    outcome: CacheOutcome,
+    served_by: &str,
+    failover: bool,
+) {
+    let latency_ms = started.elapsed().as_secs_f64() * 1000.0;
+    let bytes = content_length.parse::<u64>().unwrap_or(0);
+    metrics.record(derived.artifact_type, status, latency_ms, bytes);
+
+    info!(
+        target: "cran",
+        path=%path,
+        status=%status,
+        latency_ms=%started.elapsed().as_millis(),
+        ua=%ua,
+        range=%range,
+        etag_out=%etag_out,
+        content_length=%content_length,
+        cache=%outcome.as_str(),
+        upstream=%served_by,
+        failover=%failover,
+        derived=%serde_json::to_string(derived).unwrap(),
+        "proxied"
+    );
+
+    if let Some(access_log) = access_log {
+        access_log.log(AccessLogEntry {
+            method,
+            path,
+            status,
+            latency_ms: started.elapsed().as_millis(),
+            bytes: content_length,
+            ua,
+            artifact_type: derived.artifact_type,
+            package: derived.package.as_deref(),
+            version: derived.version.as_deref(),
+        });
+    }
+}
+
+/// Send the request to upstream mirrors in priority order, failing over to
+/// the next mirror on connect error, timeout, or 5xx. Returns the response
+/// together with the mirror that served it and whether failover occurred.
+async fn send_with_failover(
+    state: &AppState,
+    method: &Method,
+    path: &str,
+    query: Option<&str>,
+    headers: &HeaderMap,
+    body: Option<&bytes::Bytes>,
+) -> Result<(reqwest::Response, Url, bool), String> {
+    let candidates = state.mirrors.ordered();
+    let mut last_err = "no upstream mirrors configured".to_string();
+
+    for (i, mirror) in candidates.iter().enumerate() {
+        let mut target = mirror.clone();
+        target.set_path(path);
+        target.set_query(query);
+
+        let mut req = state.client.request(method.clone(), target).headers(headers.clone());
+        if let Some(b) = body {
+            req = req.body(b.clone());
+        }
+
+        match req.send().await {
+            Ok(r) if r.status().is_server_error() => {
+                tracing::warn!(upstream=%mirror, status=%r.status(), %path, "mirror_failover_5xx");
+                state.mirrors.mark(mirror, false);
+                last_err = format!("{mirror} returned {}", r.status());
+                if i + 1 == candidates.len() {
+                    return Ok((r, mirror.clone(), i > 0));
+                }
+            }
+            Ok(r) => return Ok((r, mirror.clone(), i > 0)),
+            Err(e) => {
+                tracing::warn!(upstream=%mirror, error=%e, %path, "mirror_failover_error");
+                state.mirrors.mark(mirror, false);
+                last_err = e.to_string();
+            }
+        }
+    }
+    Err(last_err)
+}
+
+/// `Cache-Control` to send for a given artifact type: immutable versioned
+/// artifacts are cacheable forever, indexes get a short revalidated window,
+/// and `unknown` paths are left alone (upstream's own headers pass through).
+fn cache_control_for(artifact_type: &str, config: &Config) -> Option<String> {
+    if is_immutable_artifact(artifact_type) {
+        Some(format!("public, max-age={}, immutable", config.immutable_max_age_secs))
+    } else if matches!(artifact_type, "index_text" | "index_gz" | "index_rds") {
+        Some(format!("public, max-age={}, must-revalidate", config.index_max_age_secs))
+    } else {
+        None
+    }
+}
+
+/// Rebuild a client response straight from a cached body + metadata, without
+/// contacting upstream.
+async fn build_cached_response(cache: &Cache, key: &str, meta: &CachedMeta, config: &Config) -> std::io::Result<Response> {
+    let file = cache.open_body(key).await?;
+    let stream = tokio_util::io::ReaderStream::new(file);
+    let body = Body::from_stream(stream);
+
+    let mut builder = Response::builder().status(StatusCode::OK);
+    if let Some(ct) = &meta.content_type {
+        builder = builder.header(header::CONTENT_TYPE, ct);
+    }
+    if let Some(len) = meta.content_length {
+        builder = builder.header(header::CONTENT_LENGTH, len.to_string());
+    }
+    if let Some(etag) = &meta.etag {
+        builder = builder.header(header::ETAG, etag);
+    }
+    if let Some(lm) = &meta.last_modified {
+        builder = builder.header(header::LAST_MODIFIED, lm);
+    }
+    // Everything that reaches the cache is either an immutable artifact or
+    // an index file, never `unknown`, so this always has an opinion.
+    let artifact_type = if meta.immutable { "src_tar" } else { "index_text" };
+    if let Some(cc) = cache_control_for(artifact_type, config) {
+        builder = builder.header(header::CACHE_CONTROL, cc);
+    }
+    Ok(builder.body(body).unwrap())
+}
+
+/// Stream `resp`'s body to the client while simultaneously writing it to the
+/// cache. The write is only made visible (renamed into place) once the
+/// stream finishes successfully and the byte count matches `content_length`
+/// (when known); otherwise the partial write is discarded.
+fn tee_to_cache(
+    cache: Cache,
+    key: String,
+    resp: reqwest::Response,
+    content_length: Option<u64>,
+    mut meta: CachedMeta,
+) -> Body {
+    let (tx, rx) = tokio::sync::mpsc::channel::<Result<bytes::Bytes, std::io::Error>>(16);
+
+    tokio::spawn(async move {
+        let mut pending = match cache.begin_write(&key).await {
+            Ok(p) => p,
+            Err(e) => {
+                tracing::warn!(error=%e, "cache_write_open_failed");
+                let mut stream = resp.bytes_stream();
+                while let Some(chunk) = stream.next().await {
+                    let forward = chunk.map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e));
+                    if tx.send(forward).await.is_err() {
+                        return;
+                    }
+                }
+                return;
+            }
+        };
+
+        let mut stream = resp.bytes_stream();
+        let mut ok = true;
+        while let Some(chunk) = stream.next().await {
+            match chunk {
+                Ok(bytes) => {
+                    if let Err(e) = pending.write_chunk(&bytes).await {
+                        tracing::warn!(error=%e, "cache_write_failed");
+                        ok = false;
+                    }
+                    if tx.send(Ok(bytes)).await.is_err() {
+                        ok = false;
+                        break;
+                    }
+                }
+                Err(e) => {
+                    let _ = tx.send(Err(std::io::Error::new(std::io::ErrorKind::Other, e))).await;
+                    ok = false;
+                    break;
+                }
+            }
+        }
+
+        let size_ok = content_length.map_or(true, |expected| expected == pending.written());
+        if ok && size_ok {
+            meta.content_length = Some(pending.written());
+            if let Err(e) = pending.commit(meta).await {
+                tracing::warn!(error=%e, "cache_commit_failed");
+            }
+        } else {
+            pending.abort().await;
+        }
+    });
+
+    Body::from_stream(tokio_stream::wrappers::ReceiverStream::new(rx))
+}
+
 async fn proxy(
     State(state): State<AppState>,
     method: Method,
@@ -106,63 +348,116 @@ async fn proxy(
     OriginalUri(uri): OriginalUri,
     body: Body,
 ) -> Result<Response, (StatusCode, String)> {
-    // Build target URL from base + path/query
-    let mut target = state.upstream.clone();
-    target.set_path(uri.path());
-    target.set_query(uri.query());
+    let path = uri.path().to_string();
+    let derived = parse_cran(&path);
+    let cache_key = Cache::key_for(&path);
+    let immutable = is_immutable_artifact(derived.artifact_type);
+    let cacheable = matches!(method, Method::GET | Method::HEAD) && derived.artifact_type != "unknown";
+    // HEAD responses are eligible to *read* from the cache (and short-circuit
+    // via a hit/revalidation like GET does), but must never be *written*:
+    // a HEAD to an uncached artifact has no body to verify, so a upstream
+    // response with a missing/zero Content-Length would otherwise commit a
+    // bogus empty entry that gets served forever afterwards.
+    let cache_writable = method == Method::GET && derived.artifact_type != "unknown";
+
+    let started = Instant::now();
+    let ua = headers.get("user-agent").and_then(|v| v.to_str().ok()).unwrap_or("-").to_string();
+    let range = headers.get("range").and_then(|v| v.to_str().ok()).unwrap_or("-").to_string();
+    // Cached entries are always served in full; a Range request is forwarded
+    // straight to upstream instead so callers resuming a partial download
+    // still get a real 206 rather than a silently-ignored Range on a 200.
+    let has_range = headers.contains_key(header::RANGE);
+    let accept_encoding = headers.get(header::ACCEPT_ENCODING).and_then(|v| v.to_str().ok()).map(String::from);
+    // Only the plain-text PACKAGES index is ever compressed, so this is the
+    // single place that decides it; everything downstream just checks it.
+    let compress_encoding = if state.config.compress_index_responses && derived.artifact_type == "index_text" {
+        accept_encoding.as_deref().and_then(compress::negotiate)
+    } else {
+        None
+    };
+
+    // Immutable artifacts never need revalidation: a cache hit is served
+    // straight from disk with no upstream round-trip at all. Never
+    // compressed: immutable artifacts are never `index_text`.
+    if cacheable && immutable && !has_range {
+        if let Some(meta) = state.cache.lookup(&cache_key).await {
+            if let Ok(resp) = build_cached_response(&state.cache, &cache_key, &meta, &state.config).await {
+                let etag_out = meta.etag.as_deref().unwrap_or("-");
+                let len_out = meta.content_length.map(|l| l.to_string()).unwrap_or_else(|| "-".into());
+                log_proxied(&state.metrics, state.access_log.as_ref(), method.as_str(), &path, resp.status().as_u16(), started, &ua, &range, etag_out, &len_out, &derived, CacheOutcome::Hit, "-", false);
+                return Ok(resp);
+            }
+        }
+    }
+
+    // Indexes are cached too, but always revalidated against upstream.
+    let cached_meta = if cacheable && !immutable && !has_range {
+        state.cache.lookup(&cache_key).await
+    } else {
+        None
+    };
 
-    // Prepare outgoing request
-    let mut req = state.client.request(method.clone(), target);
-    {
-        // Copy headers minus hop-by-hop; let reqwest compute Host
-        strip_hop_headers(&mut headers);
-        req = req.headers(headers.clone());
+    // Copy headers minus hop-by-hop; let reqwest compute Host
+    strip_hop_headers(&mut headers);
+    if let Some(meta) = &cached_meta {
+        if let Some(etag) = meta.etag.as_deref().and_then(|v| HeaderValue::from_str(v).ok()) {
+            headers.insert(header::IF_NONE_MATCH, etag);
+        } else if let Some(lm) = meta.last_modified.as_deref().and_then(|v| HeaderValue::from_str(v).ok()) {
+            headers.insert(header::IF_MODIFIED_SINCE, lm);
+        }
     }
 
     // Proxy body only when not GET/HEAD (rare for CRAN)
-    let req = if matches!(method, Method::GET | Method::HEAD) {
-        req
+    let body_bytes = if matches!(method, Method::GET | Method::HEAD) {
+        None
     } else {
         use http_body_util::BodyExt;
         let bytes = body.collect().await
             .map_err(|_| (StatusCode::BAD_REQUEST, "invalid request body".into()))?
             .to_bytes();
-        req.body(bytes)
+        Some(bytes)
     };
 
-    // Start timer & collect some request metadata
-    let started = std::time::Instant::now();
-    let ua = headers.get("user-agent").and_then(|v| v.to_str().ok()).unwrap_or("-");
-    let range = headers.get("range").and_then(|v| v.to_str().ok()).unwrap_or("-");
-    let path = uri.path();
-    let derived = parse_cran(path);
-
-    // Send to upstream
-    let resp = match req.send().await {
+    // Send to upstream, trying each mirror in turn on connect error, timeout, or 5xx
+    let (resp, served_by, failover) = match send_with_failover(
+        &state, &method, &path, uri.query(), &headers, body_bytes.as_ref(),
+    ).await {
         Ok(r) => r,
         Err(e) => {
             tracing::warn!(error=%e, %path, %ua, "upstream_error");
             return Err((StatusCode::BAD_GATEWAY, "upstream error".into()));
         }
     };
+    let served_by = served_by.to_string();
 
-    let status = resp.status();
-    let etag_out = resp.headers().get("etag").and_then(|v| v.to_str().ok()).unwrap_or("-");
-    let content_length = resp.headers().get("content-length").and_then(|v| v.to_str().ok()).unwrap_or("-");
+    if resp.status() == StatusCode::NOT_MODIFIED {
+        if let Some(meta) = cached_meta {
+            if let Ok(r) = build_cached_response(&state.cache, &cache_key, &meta, &state.config).await {
+                let etag_out = meta.etag.as_deref().unwrap_or("-").to_string();
+                if let Some(encoding) = compress_encoding {
+                    // Don't log/record bytes yet: compression changes the
+                    // byte count, so logging has to wait until the
+                    // compressed stream actually finishes.
+                    let (mut parts, body) = r.into_parts();
+                    let body = compress::compress_body(encoding, body, &mut parts.headers);
+                    let r = Response::from_parts(parts, body);
+                    let r = log_after_stream(
+                        r, state.metrics.clone(), state.access_log.clone(), method.as_str().to_string(), path.clone(),
+                        StatusCode::OK.as_u16(), started, ua.clone(), range.clone(), etag_out, derived.clone(),
+                        CacheOutcome::Revalidated, served_by, failover,
+                    );
+                    return Ok(r);
+                }
+                let len_out = meta.content_length.map(|l| l.to_string()).unwrap_or_else(|| "-".into());
+                log_proxied(&state.metrics, state.access_log.as_ref(), method.as_str(), &path, StatusCode::OK.as_u16(), started, &ua, &range, &etag_out, &len_out, &derived, CacheOutcome::Revalidated, &served_by, failover);
+                return Ok(r);
+            }
+        }
+    }
 
-    // Log one structured line
-    info!(
-        target: "cran",
-        path=%path,
-        status=%status.as_u16(),
-        latency_ms=%started.elapsed().as_millis(),
-        ua=%ua,
-        range=%range,
-        etag_out=%etag_out,
-        content_length=%content_length,
-        derived=%serde_json::to_string(&derived).unwrap(),
-        "proxied"
-    );
+    let status = resp.status();
+    let etag_out = resp.headers().get("etag").and_then(|v| v.to_str().ok()).unwrap_or("-").to_string();
+    let content_length_num = resp.headers().get(header::CONTENT_LENGTH).and_then(|v| v.to_str().ok()).and_then(|v| v.parse::<u64>().ok());
 
     // Build response back to client
     let mut builder = Response::builder().status(status);
@@ -172,11 +467,109 @@ async fn proxy(
         out_headers.insert(k, v.clone());
     }
     strip_hop_headers(&mut out_headers);
+    if status == StatusCode::OK {
+        if let Some(cc) = cache_control_for(derived.artifact_type, &state.config) {
+            out_headers.insert(header::CACHE_CONTROL, HeaderValue::from_str(&cc).unwrap());
+        }
+    }
     *builder.headers_mut().unwrap() = out_headers;
 
-    // Stream body back
+    let outcome = if cache_writable && status == StatusCode::OK { CacheOutcome::Miss } else { CacheOutcome::Bypass };
+
+    if cache_writable && status == StatusCode::OK {
+        let meta = CachedMeta {
+            etag: resp.headers().get(header::ETAG).and_then(|v| v.to_str().ok()).map(String::from),
+            last_modified: resp.headers().get(header::LAST_MODIFIED).and_then(|v| v.to_str().ok()).map(String::from),
+            content_type: resp.headers().get(header::CONTENT_TYPE).and_then(|v| v.to_str().ok()).map(String::from),
+            content_length: content_length_num,
+            immutable,
+        };
+        let body = tee_to_cache(state.cache.clone(), cache_key, resp, content_length_num, meta);
+        let resp = builder.body(body).unwrap();
+        let resp = if let Some(encoding) = compress_encoding {
+            // Compression invalidates the upstream Content-Length we'd
+            // otherwise log.
+            let (mut parts, body) = resp.into_parts();
+            let body = compress::compress_body(encoding, body, &mut parts.headers);
+            Response::from_parts(parts, body)
+        } else {
+            resp
+        };
+        // Log with the real streamed/tee'd byte count rather than the
+        // upstream Content-Length: that header is absent on some mirrors,
+        // and compression changes it anyway.
+        let resp = log_after_stream(
+            resp, state.metrics.clone(), state.access_log.clone(), method.as_str().to_string(), path.clone(),
+            status.as_u16(), started, ua.clone(), range.clone(), etag_out.clone(), derived.clone(),
+            outcome, served_by.clone(), failover,
+        );
+        return Ok(resp);
+    }
+
+    // Stream body back without caching (unknown artifact types, non-2xx,
+    // etc.) — never compressed, since `compress_encoding` is only applied to
+    // a 200 GET of `index_text`, which is handled above. Logged the
+    // same deferred way so a missing upstream Content-Length doesn't record
+    // 0 bytes streamed.
     use futures_util::TryStreamExt;
     let stream = resp.bytes_stream().map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e));
     let body = axum::body::Body::from_stream(stream);
-    Ok(builder.body(body).unwrap())
+    let resp = builder.body(body).unwrap();
+    let resp = log_after_stream(
+        resp, state.metrics.clone(), state.access_log.clone(), method.as_str().to_string(), path.clone(),
+        status.as_u16(), started, ua.clone(), range.clone(), etag_out.clone(), derived.clone(),
+        outcome, served_by.clone(), failover,
+    );
+    Ok(resp)
+}
+
+/// Wrap `resp`'s body so bytes are counted as they're streamed to the
+/// client, then log/record the request with the *actual* streamed byte
+/// count once the stream finishes. Used whenever the upstream
+/// `Content-Length` captured before this call can't be trusted as the final
+/// byte count — because the body gets recompressed, or because upstream
+/// never sent the header in the first place.
+#[allow(clippy::too_many_arguments)]
+fn log_after_stream(
+    resp: Response,
+    metrics: Arc<Metrics>,
+    access_log: Option<AccessLog>,
+    method: String,
+    path: String,
+    status: u16,
+    started: Instant,
+    ua: String,
+    range: String,
+    etag_out: String,
+    derived: CranInfo,
+    outcome: CacheOutcome,
+    served_by: String,
+    failover: bool,
+) -> Response {
+    let (parts, body) = resp.into_parts();
+    let (tx, rx) = tokio::sync::mpsc::channel::<Result<bytes::Bytes, std::io::Error>>(16);
+
+    tokio::spawn(async move {
+        let mut stream = body.into_data_stream();
+        let mut total: u64 = 0;
+        loop {
+            match stream.next().await {
+                Some(Ok(chunk)) => {
+                    total += chunk.len() as u64;
+                    if tx.send(Ok(chunk)).await.is_err() {
+                        break;
+                    }
+                }
+                Some(Err(e)) => {
+                    let _ = tx.send(Err(std::io::Error::new(std::io::ErrorKind::Other, e))).await;
+                    break;
+                }
+                None => break,
+            }
+        }
+        let content_length = total.to_string();
+        log_proxied(&metrics, access_log.as_ref(), &method, &path, status, started, &ua, &range, &etag_out, &content_length, &derived, outcome, &served_by, failover);
+    });
+
+    Response::from_parts(parts, Body::from_stream(tokio_stream::wrappers::ReceiverStream::new(rx)))
 }
@@ -0,0 +1,96 @@
+//! In-process Prometheus metrics: request counters by artifact type and
+//! status class, an upstream latency histogram, and a bytes-streamed
+//! counter. There's no push path — `/metrics` renders the current state of
+//! this registry on demand.
+
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+
+const LATENCY_BUCKETS_MS: [f64; 10] = [5.0, 10.0, 25.0, 50.0, 100.0, 250.0, 500.0, 1000.0, 2500.0, 5000.0];
+
+pub struct Metrics {
+    requests: Mutex<HashMap<(&'static str, &'static str), u64>>,
+    latency_buckets: [AtomicU64; LATENCY_BUCKETS_MS.len()],
+    latency_count: AtomicU64,
+    latency_sum_ms: Mutex<f64>,
+    bytes_total: AtomicU64,
+}
+
+impl Metrics {
+    pub fn new() -> Self {
+        Self {
+            requests: Mutex::new(HashMap::new()),
+            latency_buckets: std::array::from_fn(|_| AtomicU64::new(0)),
+            latency_count: AtomicU64::new(0),
+            latency_sum_ms: Mutex::new(0.0),
+            bytes_total: AtomicU64::new(0),
+        }
+    }
+
+    /// Record one completed request. Called right before the structured
+    /// `info!` log line for the same request, so the two never drift apart.
+    pub fn record(&self, artifact_type: &'static str, status: u16, latency_ms: f64, bytes: u64) {
+        let class = status_class(status);
+        {
+            let mut map = self.requests.lock().unwrap();
+            *map.entry((artifact_type, class)).or_insert(0) += 1;
+        }
+
+        for (bound, bucket) in LATENCY_BUCKETS_MS.iter().zip(self.latency_buckets.iter()) {
+            if latency_ms <= *bound {
+                bucket.fetch_add(1, Ordering::Relaxed);
+            }
+        }
+        self.latency_count.fetch_add(1, Ordering::Relaxed);
+        *self.latency_sum_ms.lock().unwrap() += latency_ms;
+
+        self.bytes_total.fetch_add(bytes, Ordering::Relaxed);
+    }
+
+    /// Render the registry in Prometheus text exposition format.
+    pub fn render(&self) -> String {
+        let mut out = String::new();
+
+        out.push_str("# HELP smartcran_requests_total Proxied requests by artifact type and status class.\n");
+        out.push_str("# TYPE smartcran_requests_total counter\n");
+        {
+            let map = self.requests.lock().unwrap();
+            for ((artifact_type, class), count) in map.iter() {
+                out.push_str(&format!(
+                    "smartcran_requests_total{{artifact_type=\"{artifact_type}\",status=\"{class}\"}} {count}\n"
+                ));
+            }
+        }
+
+        out.push_str("# HELP smartcran_upstream_latency_ms Upstream request latency in milliseconds.\n");
+        out.push_str("# TYPE smartcran_upstream_latency_ms histogram\n");
+        // `record` already stores each bucket as the cumulative count of
+        // observations <= its bound, so buckets are emitted as-is; summing
+        // them again here would double-accumulate and break monotonicity.
+        for (bound, bucket) in LATENCY_BUCKETS_MS.iter().zip(self.latency_buckets.iter()) {
+            let count = bucket.load(Ordering::Relaxed);
+            out.push_str(&format!("smartcran_upstream_latency_ms_bucket{{le=\"{bound}\"}} {count}\n"));
+        }
+        let total = self.latency_count.load(Ordering::Relaxed);
+        out.push_str(&format!("smartcran_upstream_latency_ms_bucket{{le=\"+Inf\"}} {total}\n"));
+        out.push_str(&format!("smartcran_upstream_latency_ms_sum {}\n", *self.latency_sum_ms.lock().unwrap()));
+        out.push_str(&format!("smartcran_upstream_latency_ms_count {total}\n"));
+
+        out.push_str("# HELP smartcran_bytes_streamed_total Bytes streamed back to clients.\n");
+        out.push_str("# TYPE smartcran_bytes_streamed_total counter\n");
+        out.push_str(&format!("smartcran_bytes_streamed_total {}\n", self.bytes_total.load(Ordering::Relaxed)));
+
+        out
+    }
+}
+
+fn status_class(status: u16) -> &'static str {
+    match status / 100 {
+        2 => "2xx",
+        3 => "3xx",
+        4 => "4xx",
+        5 => "5xx",
+        _ => "other",
+    }
+}
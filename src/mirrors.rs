@@ -0,0 +1,126 @@
+//! Ordered pool of upstream CRAN mirrors with background health checks and
+//! failover on connect error, 5xx, or timeout.
+
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+use url::Url;
+
+struct MirrorState {
+    base: Url,
+    healthy: AtomicBool,
+}
+
+#[derive(Clone)]
+pub struct MirrorPool {
+    mirrors: Arc<Vec<MirrorState>>,
+}
+
+impl MirrorPool {
+    pub fn new(bases: Vec<Url>) -> Self {
+        let mirrors = bases
+            .into_iter()
+            .map(|base| MirrorState { base, healthy: AtomicBool::new(true) })
+            .collect();
+        Self { mirrors: Arc::new(mirrors) }
+    }
+
+    /// Mirrors in try-order: healthy ones first (in their configured
+    /// order), then unhealthy ones as a last resort rather than dropped
+    /// entirely, since a failed health probe can be a false negative.
+    pub fn ordered(&self) -> Vec<Url> {
+        let (mut healthy, mut unhealthy) = (Vec::new(), Vec::new());
+        for m in self.mirrors.iter() {
+            if m.healthy.load(Ordering::Relaxed) {
+                healthy.push(m.base.clone());
+            } else {
+                unhealthy.push(m.base.clone());
+            }
+        }
+        healthy.extend(unhealthy);
+        healthy
+    }
+
+    pub fn mark(&self, base: &Url, healthy: bool) {
+        if let Some(m) = self.mirrors.iter().find(|m| &m.base == base) {
+            let was_healthy = m.healthy.swap(healthy, Ordering::Relaxed);
+            if was_healthy != healthy {
+                tracing::warn!(upstream=%base, healthy, "mirror_health_changed");
+            }
+        }
+    }
+
+    /// Resolve and TLS-handshake every mirror up front so the first real
+    /// request isn't the one paying for a cold connection.
+    pub async fn warm_up(&self, client: &reqwest::Client) {
+        for m in self.mirrors.iter() {
+            let mut probe = m.base.clone();
+            probe.set_path("/src/contrib/PACKAGES");
+            match client.head(probe).send().await {
+                Ok(_) => tracing::info!(upstream=%m.base, "mirror_warmed"),
+                Err(e) => tracing::warn!(upstream=%m.base, error=%e, "mirror_warmup_failed"),
+            }
+        }
+    }
+
+    /// Periodically probe each mirror's health; intended to run as a
+    /// background task for the lifetime of the process.
+    pub async fn run_health_checks(self, client: reqwest::Client, interval: Duration) {
+        let mut ticker = tokio::time::interval(interval);
+        ticker.tick().await; // first tick fires immediately; skip it, warm_up already probed
+        loop {
+            ticker.tick().await;
+            for m in self.mirrors.iter() {
+                let mut probe = m.base.clone();
+                probe.set_path("/src/contrib/PACKAGES");
+                let healthy = match client.head(probe).send().await {
+                    Ok(r) => r.status().is_success() || r.status().is_redirection(),
+                    Err(_) => false,
+                };
+                self.mark(&m.base, healthy);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn urls(raw: &[&str]) -> Vec<Url> {
+        raw.iter().map(|u| Url::parse(u).unwrap()).collect()
+    }
+
+    #[test]
+    fn ordered_keeps_configured_order_when_all_healthy() {
+        let pool = MirrorPool::new(urls(&["https://a.example/", "https://b.example/", "https://c.example/"]));
+        assert_eq!(pool.ordered(), urls(&["https://a.example/", "https://b.example/", "https://c.example/"]));
+    }
+
+    #[test]
+    fn ordered_moves_unhealthy_mirrors_to_the_end_but_keeps_them() {
+        let mirrors = urls(&["https://a.example/", "https://b.example/", "https://c.example/"]);
+        let pool = MirrorPool::new(mirrors.clone());
+        pool.mark(&mirrors[0], false);
+        assert_eq!(pool.ordered(), urls(&["https://b.example/", "https://c.example/", "https://a.example/"]));
+    }
+
+    #[test]
+    fn mark_healthy_again_restores_try_first_priority() {
+        let mirrors = urls(&["https://a.example/", "https://b.example/"]);
+        let pool = MirrorPool::new(mirrors.clone());
+        pool.mark(&mirrors[0], false);
+        pool.mark(&mirrors[0], true);
+        assert_eq!(pool.ordered(), urls(&["https://a.example/", "https://b.example/"]));
+    }
+
+    #[test]
+    fn mark_unknown_mirror_is_a_no_op() {
+        let mirrors = urls(&["https://a.example/"]);
+        let pool = MirrorPool::new(mirrors.clone());
+        let unrelated = Url::parse("https://not-configured.example/").unwrap();
+        pool.mark(&unrelated, false);
+        assert_eq!(pool.ordered(), mirrors);
+    }
+}